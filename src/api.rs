@@ -1,5 +1,8 @@
+use crate::biquad_filter::{design_coefficients, BiquadFilter, BiquadKind};
+use crate::edf::{SharedRecorder, SignalSpec};
 use crate::ncpt;
 use crate::task::TaskSignal;
+use crate::time::clock_gettime;
 use core::fmt::Debug;
 use defmt::warn;
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
@@ -15,7 +18,7 @@ use serde::de::Deserializer;
 use serde::{Deserialize, Serialize};
 
 /// The transducer type used to measure a signal.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 enum TransducerType {
     #[serde(rename = "MEMSPressureTransducer")]
     MEMSPressureTransducer,
@@ -38,6 +41,15 @@ enum Unit {
     Pascals,
 }
 
+impl TransducerType {
+    /// The EDF transducer-type label for this transducer.
+    fn label(self) -> &'static str {
+        match self {
+            TransducerType::MEMSPressureTransducer => "MEMS pressure transducer",
+        }
+    }
+}
+
 impl Unit {
     fn to_string(self) -> &'static str {
         match self {
@@ -81,6 +93,50 @@ struct Filter {
     frequency: f32,
 }
 
+impl Filter {
+    /// The default quality factor for high- and low-pass sections.
+    const DEFAULT_Q: f32 = 0.707;
+
+    /// Synthesize the `BiquadFilter` coefficients that realize this declared
+    /// filter at the given `sample_rate`, so the prefiltering reported by
+    /// [`RpcHandler::signals`] and the coefficients actually applied in
+    /// `ncpt::sample` are derived from a single source and cannot drift apart.
+    ///
+    /// High- and low-pass sections use [`DEFAULT_Q`](Self::DEFAULT_Q); notches
+    /// take an explicit `q` (the 4 Hz anti-alias notch uses `Q = 0.5`). Returns
+    /// `None` if the frequency is not below the Nyquist frequency.
+    pub fn coefficients(&self, sample_rate: u32, q: Option<f32>) -> Option<([f32; 3], [f32; 3])> {
+        let kind = match self.kind {
+            FilterKind::HighPass => BiquadKind::HighPass,
+            FilterKind::LowPass => BiquadKind::LowPass,
+            FilterKind::Notch => BiquadKind::Notch,
+        };
+
+        design_coefficients(
+            kind,
+            sample_rate as f32,
+            self.frequency,
+            q.unwrap_or(Self::DEFAULT_Q),
+        )
+    }
+
+    /// Build a ready-to-run `BiquadFilter` realizing this declared filter at
+    /// the given `sample_rate`. Notches use `Q = 0.5` (matching the legacy 4 Hz
+    /// anti-alias notch); high- and low-pass sections use the default Q.
+    /// Returns `None` if the frequency is not below the Nyquist frequency.
+    pub fn to_biquad<T>(&self, sample_rate: u32) -> Option<BiquadFilter<T>>
+    where
+        T: num_traits::FromPrimitive + num_traits::ToPrimitive,
+    {
+        let q = match self.kind {
+            FilterKind::Notch => Some(0.5),
+            _ => None,
+        };
+        let (numerator, denominator) = self.coefficients(sample_rate, q)?;
+        Some(BiquadFilter::new(numerator, denominator))
+    }
+}
+
 const MAX_FILTERS: usize = 8;
 
 #[derive(Debug)]
@@ -88,6 +144,30 @@ struct FilterList {
     filters: Vec<Filter, MAX_FILTERS>,
 }
 
+impl FilterList {
+    /// Render the filter list as its serialized string form (e.g. `HP:0.10Hz
+    /// N:4.00Hz`), for use in the EDF prefiltering field.
+    fn format(&self) -> String<80> {
+        let mut out: String<80> = String::new();
+        for filter in &self.filters {
+            let kind_str = match filter.kind {
+                FilterKind::HighPass => "HP",
+                FilterKind::LowPass => "LP",
+                FilterKind::Notch => "N",
+            };
+            if !out.is_empty() {
+                out.push(' ').ok();
+            }
+            core::fmt::write(
+                &mut out,
+                format_args!("{}:{:.2}{}", kind_str, filter.frequency, filter.unit.to_string()),
+            )
+            .ok();
+        }
+        out
+    }
+}
+
 impl<'de> serde::Deserialize<'de> for FilterList {
     fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
         let filters_str = String::<64>::deserialize(d)?;
@@ -196,6 +276,69 @@ struct Signal<'a> {
     sample_rate: u32,
 }
 
+/// The maximum number of concurrently acquired signals.
+pub const MAX_SIGNALS: usize = 8;
+
+/// A runtime description of one acquired signal and the channel used to
+/// start/stop its sampling task. The sampler reads its rate, range, scaling,
+/// and filter chain from here rather than baking them in.
+pub struct SignalDescriptor {
+    /// The unique identifier of the signal.
+    pub id: u32,
+    /// The human-readable name of the signal.
+    pub name: &'static str,
+    /// The type of transducer used to measure the signal.
+    pub transducer_type: TransducerType,
+    /// The unit of the signal.
+    pub unit: Unit,
+    /// The minimum value of the signal, in its unit.
+    pub min: f32,
+    /// The maximum value of the signal, in its unit.
+    pub max: f32,
+    /// The full-scale range of the transducer at the configured gain, in the
+    /// signal's unit. Raw i24 ADC counts are scaled against this.
+    pub full_scale: f32,
+    /// The sample rate of the signal, in Hertz.
+    pub sample_rate: u32,
+    /// The prefiltering applied to the signal.
+    pub filters: Vec<Filter, MAX_FILTERS>,
+    /// The channel used to start and stop this signal's sampling task.
+    pub signal: &'static EmbassySignal<ThreadModeRawMutex, TaskSignal>,
+}
+
+impl SignalDescriptor {
+    /// Build the descriptor for the nasal-pressure transducer (signal 1).
+    pub fn nasal_pressure(
+        signal: &'static EmbassySignal<ThreadModeRawMutex, TaskSignal>,
+    ) -> Self {
+        SignalDescriptor {
+            id: ncpt::NCPT_SIGNAL_ID,
+            name: "Nasal Pressure",
+            transducer_type: TransducerType::MEMSPressureTransducer,
+            unit: Unit::Pascals,
+            min: -200.0,
+            max: 200.0,
+            // 10.4 kPa full scale at the configured gain.
+            full_scale: 10_400.0,
+            sample_rate: 40,
+            filters: Vec::from_slice(&[
+                Filter {
+                    kind: FilterKind::HighPass,
+                    unit: Unit::Hertz,
+                    frequency: 0.1,
+                },
+                Filter {
+                    kind: FilterKind::Notch,
+                    unit: Unit::Hertz,
+                    frequency: 4.0,
+                },
+            ])
+            .unwrap(),
+            signal,
+        }
+    }
+}
+
 /// The values of a signal at a given timestamp.
 #[derive(Debug, Serialize)]
 pub struct SignalValues<'a, T: Serialize> {
@@ -208,20 +351,18 @@ pub struct SignalValues<'a, T: Serialize> {
 }
 
 pub struct RpcHandler {
-    ncpt_sampling_task_signals:
-        &'static EmbassySignal<ThreadModeRawMutex, TaskSignal>,
+    registry: &'static [SignalDescriptor],
+    recorder: &'static SharedRecorder,
 }
 
 impl RpcHandler {
-    pub fn new(
-        ncpt_sampling_task_signals: &'static EmbassySignal<
-            ThreadModeRawMutex,
-            TaskSignal,
-        >,
-    ) -> Self {
-        Self {
-            ncpt_sampling_task_signals,
-        }
+    pub fn new(registry: &'static [SignalDescriptor], recorder: &'static SharedRecorder) -> Self {
+        Self { registry, recorder }
+    }
+
+    /// Look up a signal descriptor by its identifier.
+    fn descriptor(&self, id: u32) -> Option<&SignalDescriptor> {
+        self.registry.iter().find(|d| d.id == id)
     }
 
     async fn signals<'a>(
@@ -229,30 +370,23 @@ impl RpcHandler {
         id: Option<u64>,
         response_json: &'a mut [u8],
     ) -> Result<usize, RpcError> {
-        let signals: [Signal; 1] = [Signal {
-            id: ncpt::NCPT_SIGNAL_ID,
-            name: "Nasal Pressure",
-            transducer_type: TransducerType::MEMSPressureTransducer,
-            unit: Unit::Pascals,
-            min: -200.0,
-            max: 200.0,
-            prefiltering: FilterList {
-                filters: Vec::from_slice(&[
-                    Filter {
-                        kind: FilterKind::HighPass,
-                        unit: Unit::Hertz,
-                        frequency: 0.1,
-                    },
-                    Filter {
-                        kind: FilterKind::Notch,
-                        unit: Unit::Hertz,
-                        frequency: 4.0,
+        let mut signals: Vec<Signal, MAX_SIGNALS> = Vec::new();
+        for descriptor in self.registry {
+            signals
+                .push(Signal {
+                    id: descriptor.id,
+                    name: descriptor.name,
+                    transducer_type: descriptor.transducer_type,
+                    unit: descriptor.unit,
+                    min: descriptor.min,
+                    max: descriptor.max,
+                    prefiltering: FilterList {
+                        filters: descriptor.filters.clone(),
                     },
-                ])
-                .unwrap(),
-            },
-            sample_rate: 40,
-        }];
+                    sample_rate: descriptor.sample_rate,
+                })
+                .ok();
+        }
 
         let response: RpcResponse<&[Signal]> = RpcResponse {
             jsonrpc: JSONRPC_VERSION,
@@ -264,16 +398,19 @@ impl RpcHandler {
         Ok(serde_json_core::to_slice(&response, response_json).unwrap())
     }
 
-    async fn start<'a>(
+    /// Fan a start/stop signal out to the sampling tasks for the requested set
+    /// of signal IDs. All IDs must be known before anything is signalled.
+    fn dispatch<'a>(
         &self,
         id: Option<u64>,
         request_json: &'a [u8],
         response_json: &'a mut [u8],
+        what: TaskSignal,
     ) -> Result<usize, RpcError> {
         #[derive(Debug, Deserialize, defmt::Format)]
         struct SignalIdsRequest {
             #[serde(rename = "params")]
-            signal_ids: Vec<u32, 1>,
+            signal_ids: Vec<u32, MAX_SIGNALS>,
         }
 
         let request: SignalIdsRequest = match serde_json_core::from_slice(request_json) {
@@ -284,14 +421,21 @@ impl RpcHandler {
             }
         };
 
-        if request.signal_ids.len() != 1 || request.signal_ids[0] != 1 {
+        if request.signal_ids.is_empty()
+            || request
+                .signal_ids
+                .iter()
+                .any(|id| self.descriptor(*id).is_none())
+        {
             warn!("Invalid request: {}", request);
             return Err(RpcErrorCode::InvalidParams.into());
         }
 
-        // Start sampling.
-        self.ncpt_sampling_task_signals
-            .signal(TaskSignal::Start);
+        for signal_id in &request.signal_ids {
+            if let Some(descriptor) = self.descriptor(*signal_id) {
+                descriptor.signal.signal(what);
+            }
+        }
 
         let response: RpcResponse<'static, ()> = RpcResponse {
             jsonrpc: JSONRPC_VERSION,
@@ -303,19 +447,108 @@ impl RpcHandler {
         Ok(serde_json_core::to_slice(&response, response_json).unwrap())
     }
 
+    async fn start<'a>(
+        &self,
+        id: Option<u64>,
+        request_json: &'a [u8],
+        response_json: &'a mut [u8],
+    ) -> Result<usize, RpcError> {
+        self.dispatch(id, request_json, response_json, TaskSignal::Start)
+    }
+
     async fn stop<'a>(
         &self,
         id: Option<u64>,
         request_json: &'a [u8],
         response_json: &'a mut [u8],
     ) -> Result<usize, RpcError> {
-        #[derive(Debug, Deserialize, defmt::Format)]
-        struct SignalIdsRequest {
-            #[serde(rename = "params")]
-            signal_ids: Vec<u32, 1>,
+        self.dispatch(id, request_json, response_json, TaskSignal::Stop)
+    }
+
+    /// Build the per-signal EDF header specs for the registered signals.
+    fn signal_specs(&self) -> Vec<SignalSpec, MAX_SIGNALS> {
+        let mut specs: Vec<SignalSpec, MAX_SIGNALS> = Vec::new();
+        for d in self.registry {
+            let mut label: String<16> = String::new();
+            label.push_str(d.name).ok();
+            let mut transducer: String<80> = String::new();
+            transducer.push_str(d.transducer_type.label()).ok();
+            let mut dimension: String<8> = String::new();
+            dimension.push_str(d.unit.to_string()).ok();
+
+            let prefiltering = FilterList {
+                filters: d.filters.clone(),
+            }
+            .format();
+
+            specs
+                .push(SignalSpec {
+                    id: d.id,
+                    label,
+                    transducer,
+                    dimension,
+                    physical_min: d.min,
+                    physical_max: d.max,
+                    prefiltering,
+                    samples_per_record: d.sample_rate,
+                })
+                .ok();
         }
+        specs
+    }
 
-        let request: SignalIdsRequest = match serde_json_core::from_slice(request_json) {
+    async fn record_start<'a>(
+        &self,
+        id: Option<u64>,
+        response_json: &'a mut [u8],
+    ) -> Result<usize, RpcError> {
+        let specs = self.signal_specs();
+        let start = clock_gettime().map_err(|_| RpcError::from(RpcErrorCode::InternalError))?;
+        self.recorder
+            .lock(|r| r.borrow_mut().start(&specs, &start));
+
+        let response: RpcResponse<'static, ()> = RpcResponse {
+            jsonrpc: JSONRPC_VERSION,
+            error: None,
+            result: None,
+            id,
+        };
+        Ok(serde_json_core::to_slice(&response, response_json).unwrap())
+    }
+
+    async fn record_stop<'a>(
+        &self,
+        id: Option<u64>,
+        response_json: &'a mut [u8],
+    ) -> Result<usize, RpcError> {
+        self.recorder.lock(|r| r.borrow_mut().stop());
+
+        let response: RpcResponse<'static, ()> = RpcResponse {
+            jsonrpc: JSONRPC_VERSION,
+            error: None,
+            result: None,
+            id,
+        };
+        Ok(serde_json_core::to_slice(&response, response_json).unwrap())
+    }
+
+    async fn record_read<'a>(
+        &self,
+        id: Option<u64>,
+        request_json: &'a [u8],
+        response_json: &'a mut [u8],
+    ) -> Result<usize, RpcError> {
+        #[derive(Debug, Deserialize)]
+        struct ReadParams {
+            offset: u32,
+            len: u32,
+        }
+        #[derive(Debug, Deserialize)]
+        struct ReadRequest {
+            params: ReadParams,
+        }
+
+        let request: ReadRequest = match serde_json_core::from_slice(request_json) {
             Ok((request, _remainder)) => request,
             Err(_) => {
                 warn!("Unable to parse request");
@@ -323,22 +556,24 @@ impl RpcHandler {
             }
         };
 
-        if request.signal_ids.len() != 1 || request.signal_ids[0] != 1 {
-            warn!("Invalid request: {}", request);
-            return Err(RpcErrorCode::InvalidParams.into());
-        }
+        // Read a bounded chunk and return it hex-encoded.
+        let len = (request.params.len as usize).min(240);
+        let mut chunk = [0u8; 240];
+        let read = self
+            .recorder
+            .lock(|r| r.borrow().read(request.params.offset, &mut chunk[..len]));
 
-        // Stop sampling.
-        self.ncpt_sampling_task_signals
-            .signal(TaskSignal::Stop);
+        let mut hex: String<512> = String::new();
+        for byte in &chunk[..read] {
+            core::fmt::write(&mut hex, format_args!("{:02x}", byte)).ok();
+        }
 
-        let response: RpcResponse<'static, ()> = RpcResponse {
+        let response: RpcResponse<&str> = RpcResponse {
             jsonrpc: JSONRPC_VERSION,
             error: None,
-            result: None,
+            result: Some(hex.as_str()),
             id,
         };
-
         Ok(serde_json_core::to_slice(&response, response_json).unwrap())
     }
 }
@@ -356,6 +591,9 @@ impl embedded_jsonrpc::RpcHandler for RpcHandler {
                 "openpsg.signals" => self.signals(id, response_json).await,
                 "openpsg.start" => self.start(id, request_json, response_json).await,
                 "openpsg.stop" => self.stop(id, request_json, response_json).await,
+                "openpsg.record.start" => self.record_start(id, response_json).await,
+                "openpsg.record.stop" => self.record_stop(id, response_json).await,
+                "openpsg.record.read" => self.record_read(id, request_json, response_json).await,
                 _ => Err(RpcErrorCode::MethodNotFound.into()),
             }
         })
@@ -16,7 +16,9 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use core::f64::consts::PI;
 use core::marker::PhantomData;
+use libm::{cos, sin};
 use num_traits::{FromPrimitive, ToPrimitive};
 
 /// A simple biquad filter implementation
@@ -57,6 +59,33 @@ where
         }
     }
 
+    /// Design a low-pass biquad from the sample rate `fs`, corner frequency
+    /// `f0`, and quality factor `q` using the RBJ audio-EQ cookbook.
+    pub fn low_pass(fs: f32, f0: f32, q: f32) -> Self {
+        Self::design(BiquadKind::LowPass, fs, f0, q)
+    }
+
+    /// Design a high-pass biquad from the sample rate `fs`, corner frequency
+    /// `f0`, and quality factor `q` using the RBJ audio-EQ cookbook.
+    pub fn high_pass(fs: f32, f0: f32, q: f32) -> Self {
+        Self::design(BiquadKind::HighPass, fs, f0, q)
+    }
+
+    /// Design a notch biquad (for mains 50/60 Hz artifact rejection) from the
+    /// sample rate `fs`, notch frequency `f0`, and quality factor `q`.
+    pub fn notch(fs: f32, f0: f32, q: f32) -> Self {
+        Self::design(BiquadKind::Notch, fs, f0, q)
+    }
+
+    /// Design a biquad of the given `kind`. Panics if `f0` is not below the
+    /// Nyquist frequency; callers with runtime-supplied frequencies should use
+    /// [`design_coefficients`] and handle the `None` case instead.
+    pub fn design(kind: BiquadKind, fs: f32, f0: f32, q: f32) -> Self {
+        let (numerator, denominator) =
+            design_coefficients(kind, fs, f0, q).expect("f0 must be below the Nyquist frequency");
+        Self::new(numerator, denominator)
+    }
+
     // Apply the filter to an array of samples in place.
     pub fn apply(&mut self, samples: &mut [T]) {
         for sample in samples.iter_mut() {
@@ -86,3 +115,76 @@ where
         }
     }
 }
+
+/// The response shape of an RBJ-designed biquad.
+#[derive(Clone, Copy, Debug)]
+pub enum BiquadKind {
+    LowPass,
+    HighPass,
+    Notch,
+}
+
+/// Synthesize normalized `[f32; 3]` numerator/denominator coefficients for an
+/// RBJ audio-EQ cookbook biquad of the given `kind`, sample rate `fs`, corner
+/// frequency `f0`, and quality factor `q`. Returns `None` if `f0` is not below
+/// the Nyquist frequency (`fs / 2`).
+///
+/// The math is kept in `f64` internally because sub-Hz cutoffs push the
+/// coefficients close to the unstable region before they are truncated to the
+/// `f32` arrays.
+pub fn design_coefficients(
+    kind: BiquadKind,
+    fs: f32,
+    f0: f32,
+    q: f32,
+) -> Option<([f32; 3], [f32; 3])> {
+    if f0 <= 0.0 || f0 >= fs / 2.0 {
+        return None;
+    }
+
+    let w0 = 2.0 * PI * f0 as f64 / fs as f64;
+    let cos_w0 = cos(w0);
+    let alpha = sin(w0) / (2.0 * q as f64);
+
+    let (b0, b1, b2) = match kind {
+        BiquadKind::LowPass => ((1.0 - cos_w0) / 2.0, 1.0 - cos_w0, (1.0 - cos_w0) / 2.0),
+        BiquadKind::HighPass => ((1.0 + cos_w0) / 2.0, -(1.0 + cos_w0), (1.0 + cos_w0) / 2.0),
+        BiquadKind::Notch => (1.0, -2.0 * cos_w0, 1.0),
+    };
+
+    let a0 = 1.0 + alpha;
+    let a1 = -2.0 * cos_w0;
+    let a2 = 1.0 - alpha;
+
+    // Normalize all six coefficients by a0 so denominator[0] == 1.0.
+    Some((
+        [(b0 / a0) as f32, (b1 / a0) as f32, (b2 / a0) as f32],
+        [1.0, (a1 / a0) as f32, (a2 / a0) as f32],
+    ))
+}
+
+/// A cascade of second-order sections (SOS) applied in series, enabling
+/// higher-order responses (e.g. Butterworth) built from individual biquads.
+pub struct BiquadCascade<T, const N: usize>
+where
+    T: FromPrimitive + ToPrimitive,
+{
+    stages: [BiquadFilter<T>; N],
+}
+
+impl<T, const N: usize> BiquadCascade<T, N>
+where
+    T: FromPrimitive + ToPrimitive,
+{
+    /// Create a cascade from its second-order sections.
+    pub fn new(stages: [BiquadFilter<T>; N]) -> Self {
+        BiquadCascade { stages }
+    }
+
+    /// Apply every stage to the samples in series, in place.
+    pub fn apply(&mut self, samples: &mut [T]) {
+        for stage in self.stages.iter_mut() {
+            stage.apply(samples);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! On-device EDF+ recording.
+//!
+//! Between `openpsg.record.start` and `openpsg.record.stop` the recorder
+//! assembles a real EDF+ file in a reserved internal-flash region: a 256-byte
+//! main header, one 256-byte header per active signal, then fixed one-second
+//! data records of int16 samples. `openpsg.record.read` pulls the finished
+//! file back off the device in chunks. The recording does not depend on a
+//! continuously connected host.
+
+use crate::flash::{Flash, SharedFlash};
+use crate::time::Timespec;
+use chrono::{Datelike, Timelike};
+use core::cell::RefCell;
+use core::fmt::Write as _;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use heapless::{String, Vec};
+
+/// The fixed size of the main header and of each signal header.
+const HEADER_BLOCK: usize = 256;
+
+/// The maximum number of signals a recording can describe.
+pub const MAX_SIGNALS: usize = crate::api::MAX_SIGNALS;
+
+/// The maximum number of samples any one signal contributes to a single data
+/// record, matching the sampler's per-second batch size.
+pub const MAX_SAMPLES_PER_RECORD: usize = 256;
+
+/// The largest EDF header: the main header plus one header per signal.
+const MAX_HEADER_BYTES: usize = HEADER_BLOCK * (MAX_SIGNALS + 1);
+
+/// The largest cross-signal data record: every signal's int16 samples.
+const MAX_RECORD_BYTES: usize = MAX_SIGNALS * MAX_SAMPLES_PER_RECORD * 2;
+
+/// The largest single block handed to [`EdfRecorder::program`] — a header or a
+/// data record, whichever is larger. Both are multiples of `HEADER_BLOCK`, so
+/// write-granularity padding never pushes past this bound.
+const MAX_PROGRAM_BYTES: usize = if MAX_RECORD_BYTES > MAX_HEADER_BYTES {
+    MAX_RECORD_BYTES
+} else {
+    MAX_HEADER_BYTES
+};
+
+/// A single signal's EDF header fields, owned so the header can be assembled
+/// from transient sources.
+pub struct SignalSpec {
+    pub id: u32,
+    pub label: String<16>,
+    pub transducer: String<80>,
+    pub dimension: String<8>,
+    pub physical_min: f32,
+    pub physical_max: f32,
+    pub prefiltering: String<80>,
+    pub samples_per_record: u32,
+}
+
+/// The per-signal samples staged for the data record currently being
+/// assembled: its id, how many samples belong in each record, and the samples
+/// received so far this epoch.
+struct PendingSignal {
+    id: u32,
+    samples_per_record: usize,
+    samples: Vec<i16, MAX_SAMPLES_PER_RECORD>,
+    present: bool,
+}
+
+/// An EDF+ recorder writing to a reserved flash region.
+pub struct EdfRecorder {
+    flash: &'static SharedFlash,
+    base: u32,
+    capacity: u32,
+    recording: bool,
+    write_pos: u32,
+    end_pos: u32,
+    erased_to: u32,
+    pending: Vec<PendingSignal, MAX_SIGNALS>,
+}
+
+impl EdfRecorder {
+    /// Create a recorder over the flash region `[base, base + capacity)`.
+    pub fn new(flash: &'static SharedFlash, base: u32, capacity: u32) -> Self {
+        EdfRecorder {
+            flash,
+            base,
+            capacity,
+            recording: false,
+            write_pos: base,
+            end_pos: base,
+            erased_to: base,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Whether a recording is currently in progress.
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Begin a recording: assemble and write the main and per-signal headers,
+    /// then position the cursor at the first data record. The EDF+ number of
+    /// data records is left as `-1` (unknown), which the spec permits for
+    /// recordings whose length is not known in advance.
+    pub fn start(&mut self, signals: &[SignalSpec], start: &Timespec) {
+        let mut header: Vec<u8, MAX_HEADER_BYTES> = Vec::new();
+        let header_len = build_header(&mut header, signals, start);
+
+        // Erase enough of the region to cover the header.
+        self.erased_to = self.base;
+        self.erase_through(self.base + header_len as u32);
+        self.program(self.base, &header[..header_len]);
+
+        self.write_pos = self.base + header_len as u32;
+        self.end_pos = self.write_pos;
+
+        // Seed the staging table so each epoch is assembled into one data
+        // record spanning every signal, in the declared signal order.
+        self.pending.clear();
+        for s in signals {
+            self.pending
+                .push(PendingSignal {
+                    id: s.id,
+                    samples_per_record: s.samples_per_record as usize,
+                    samples: Vec::new(),
+                    present: false,
+                })
+                .ok();
+        }
+
+        self.recording = true;
+    }
+
+    /// Stage one signal's samples for the current epoch. Once every declared
+    /// signal has reported, the concatenated cross-signal data record is
+    /// flushed to flash in signal order, as EDF+ requires.
+    pub fn append_signal(&mut self, id: u32, samples: &[i16]) {
+        if !self.recording {
+            return;
+        }
+
+        let idx = match self.pending.iter().position(|p| p.id == id) {
+            Some(idx) => idx,
+            None => return,
+        };
+
+        // Replacing an already-staged epoch means the slowest signal lagged a
+        // whole record behind; keep the newest and drop the stale partial.
+        let slot = &mut self.pending[idx];
+        slot.samples.clear();
+        let take = samples.len().min(MAX_SAMPLES_PER_RECORD);
+        slot.samples.extend_from_slice(&samples[..take]).ok();
+        slot.present = true;
+
+        if self.pending.iter().all(|p| p.present) {
+            self.flush_record();
+            for p in self.pending.iter_mut() {
+                p.samples.clear();
+                p.present = false;
+            }
+        }
+    }
+
+    /// Concatenate the staged samples of every signal into one EDF+ data record
+    /// and append it. Silently drops the record if the region is full.
+    fn flush_record(&mut self) {
+        let mut bytes: Vec<u8, MAX_RECORD_BYTES> = Vec::new();
+        for p in self.pending.iter() {
+            // Each signal contributes exactly `samples_per_record` samples per
+            // record; pad short epochs with zeros and truncate long ones. The
+            // count is bounded by the per-signal staging capacity so the record
+            // buffer can never overflow.
+            let count = p.samples_per_record.min(MAX_SAMPLES_PER_RECORD);
+            for i in 0..count {
+                let sample = p.samples.get(i).copied().unwrap_or(0);
+                bytes.extend_from_slice(&sample.to_le_bytes()).ok();
+            }
+        }
+
+        // Keep records aligned to the flash write granularity so the next
+        // record starts on a writable boundary.
+        let write_size = <Flash as NorFlash>::WRITE_SIZE.max(1) as u32;
+        let written = (bytes.len() as u32).div_ceil(write_size) * write_size;
+
+        if self.write_pos + written > self.base + self.capacity {
+            return; // Out of room; stop growing the file.
+        }
+
+        self.erase_through(self.write_pos + written);
+        self.program(self.write_pos, &bytes);
+        self.write_pos += written;
+        self.end_pos = self.write_pos;
+    }
+
+    /// Stop the current recording. The assembled file remains readable.
+    pub fn stop(&mut self) {
+        self.recording = false;
+    }
+
+    /// Read up to `len` bytes of the recorded file starting at `offset`.
+    pub fn read(&self, offset: u32, out: &mut [u8]) -> usize {
+        let start = self.base + offset;
+        if start >= self.end_pos {
+            return 0;
+        }
+        let len = out.len().min((self.end_pos - start) as usize);
+        self.flash
+            .lock(|f| f.borrow_mut().read(start, &mut out[..len]))
+            .ok();
+        len
+    }
+
+    /// Erase forward, a sector at a time, until `target` is covered.
+    fn erase_through(&mut self, target: u32) {
+        let sector = <Flash as NorFlash>::ERASE_SIZE as u32;
+        while self.erased_to < target {
+            let end = self.erased_to + sector;
+            self.flash
+                .lock(|f| f.borrow_mut().erase(self.erased_to, end))
+                .ok();
+            self.erased_to = end;
+        }
+    }
+
+    fn program(&self, offset: u32, data: &[u8]) {
+        // Pad to the flash write granularity.
+        let write_size = <Flash as NorFlash>::WRITE_SIZE.max(1);
+        let mut buf: Vec<u8, MAX_PROGRAM_BYTES> = Vec::new();
+        buf.extend_from_slice(data).ok();
+        while buf.len() % write_size != 0 {
+            buf.push(0).ok();
+        }
+        self.flash
+            .lock(|f| f.borrow_mut().write(offset, &buf))
+            .ok();
+    }
+}
+
+/// Assemble the EDF+ header (main + per-signal) into `out`, returning its
+/// length in bytes.
+fn build_header<const N: usize>(
+    out: &mut Vec<u8, N>,
+    signals: &[SignalSpec],
+    start: &Timespec,
+) -> usize {
+    let ns = signals.len();
+    let header_bytes = HEADER_BLOCK * (ns + 1);
+    out.resize(header_bytes, b' ').ok();
+
+    let (date, time) = start_fields(start);
+
+    field(out, 0, 8, "0"); // Version.
+    field(out, 8, 80, "X X X X"); // Patient identification (EDF+ anonymized).
+    field(out, 88, 80, "Startdate X X X X"); // Recording identification.
+    field(out, 168, 8, &date); // dd.mm.yy
+    field(out, 176, 8, &time); // hh.mm.ss
+    field(out, 184, 8, &num(header_bytes as i64)); // Header bytes.
+    field(out, 192, 44, "EDF+C"); // Reserved: continuous EDF+.
+    field(out, 236, 8, "-1"); // Number of data records (unknown).
+    field(out, 244, 8, "1"); // Data record duration (seconds).
+    field(out, 252, 4, &num(ns as i64)); // Number of signals.
+
+    // Per-signal fields are laid out field-major: all labels, then all
+    // transducer types, etc.
+    let mut at = HEADER_BLOCK;
+    for s in signals {
+        field(out, at, 16, &s.label);
+        at += 16;
+    }
+    for s in signals {
+        field(out, at, 80, &s.transducer);
+        at += 80;
+    }
+    for s in signals {
+        field(out, at, 8, &s.dimension);
+        at += 8;
+    }
+    for s in signals {
+        field(out, at, 8, &num(s.physical_min as i64));
+        at += 8;
+    }
+    for s in signals {
+        field(out, at, 8, &num(s.physical_max as i64));
+        at += 8;
+    }
+    for _ in signals {
+        field(out, at, 8, &num(-(i16::MAX as i64)));
+        at += 8;
+    }
+    for _ in signals {
+        field(out, at, 8, &num(i16::MAX as i64));
+        at += 8;
+    }
+    for s in signals {
+        field(out, at, 80, &s.prefiltering);
+        at += 80;
+    }
+    for s in signals {
+        field(out, at, 8, &num(s.samples_per_record as i64));
+        at += 8;
+    }
+    // Trailing reserved (32 bytes per signal) left as spaces.
+
+    header_bytes
+}
+
+/// Write `text` left-justified and space-padded into `out[at..at + width]`.
+fn field<const N: usize>(out: &mut Vec<u8, N>, at: usize, width: usize, text: &str) {
+    let bytes = text.as_bytes();
+    for i in 0..width {
+        out[at + i] = if i < bytes.len() { bytes[i] } else { b' ' };
+    }
+}
+
+/// Format an integer as its decimal ASCII representation.
+fn num(value: i64) -> String<16> {
+    let mut s: String<16> = String::new();
+    write!(s, "{}", value).ok();
+    s
+}
+
+/// Format the EDF start date (`dd.mm.yy`) and time (`hh.mm.ss`).
+fn start_fields(start: &Timespec) -> (String<8>, String<8>) {
+    let mut date: String<8> = String::new();
+    let mut time: String<8> = String::new();
+
+    if let Some(dt) = chrono::DateTime::from_timestamp(start.seconds as i64, 0) {
+        let dt = dt.naive_utc();
+        write!(
+            date,
+            "{:02}.{:02}.{:02}",
+            dt.day(),
+            dt.month(),
+            dt.year() % 100
+        )
+        .ok();
+        write!(time, "{:02}.{:02}.{:02}", dt.hour(), dt.minute(), dt.second()).ok();
+    } else {
+        date.push_str("01.01.85").ok();
+        time.push_str("00.00.00").ok();
+    }
+
+    (date, time)
+}
+
+/// A recorder shared between the RPC handler and the sampling tasks.
+pub type SharedRecorder = Mutex<ThreadModeRawMutex, RefCell<EdfRecorder>>;
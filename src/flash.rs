@@ -0,0 +1,16 @@
+//! Shared access to the STM32 internal flash.
+//!
+//! Both the store-and-forward ring ([`crate::flash_ring`]) and the EDF+
+//! recorder ([`crate::edf`]) persist to internal flash. There is a single
+//! flash peripheral, so it is wrapped once here behind a blocking mutex and
+//! shared by reference; each consumer owns a disjoint offset range.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+/// The internal flash in blocking mode.
+pub type Flash = embassy_stm32::flash::Flash<'static, embassy_stm32::flash::Blocking>;
+
+/// A flash handle shared between the ring buffer and the EDF recorder.
+pub type SharedFlash = Mutex<ThreadModeRawMutex, RefCell<Flash>>;
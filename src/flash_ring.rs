@@ -0,0 +1,171 @@
+//! A persistent ring buffer of serialized notification frames backed by the
+//! STM32 internal flash.
+//!
+//! When the JSON-RPC client disconnects, `ncpt::sample` appends the frame it
+//! could not send here instead of panicking, and drains the backlog oldest-
+//! first once the link returns. The region is addressed as a ring of
+//! fixed-size slots, one erase sector per slot, so a slot can always be erased
+//! independently before it is rewritten. Each record carries a monotonically
+//! increasing sequence number; head and tail are recovered from those
+//! sequence numbers on boot, and the newest record overwrites the oldest once
+//! the ring is full.
+
+use crate::flash::SharedFlash;
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+/// Identifies a populated slot (erased flash reads back as `0xFFFF_FFFF`).
+const RECORD_MAGIC: u32 = 0x4F50_5347; // "OPSG"
+
+/// The fixed header written at the start of every occupied slot.
+const HEADER_LEN: usize = 12; // magic + sequence + payload length
+
+/// The largest serialized frame the ring can hold, matching the JSON-RPC
+/// notification buffer in `ncpt::sample`.
+pub const MAX_RECORD: usize = 1460;
+
+/// The minimum slot size: one header plus one maximally sized frame. Callers
+/// round this up to a whole erase sector so each slot can be erased
+/// independently of its neighbours.
+pub const SLOT_MIN: usize = HEADER_LEN + MAX_RECORD;
+
+/// A flash-backed ring of length-prefixed frames. The backing flash is shared
+/// with the EDF recorder, so it is reached through a [`SharedFlash`] handle
+/// rather than owned outright; each region addresses a disjoint offset range.
+pub struct FlashRing {
+    flash: &'static SharedFlash,
+    base: u32,
+    sector_size: u32,
+    sectors: u32,
+    read_seq: u32,
+    write_seq: u32,
+}
+
+impl FlashRing {
+    /// Open the ring over `sectors` erase sectors starting at `base`, rebuilding
+    /// head/tail from the sequence numbers already present in flash.
+    pub fn new(flash: &'static SharedFlash, base: u32, sector_size: u32, sectors: u32) -> Self {
+        let mut min_seq: Option<u32> = None;
+        let mut max_seq: Option<u32> = None;
+
+        let mut header = [0u8; HEADER_LEN];
+        for slot in 0..sectors {
+            let offset = base + slot * sector_size;
+            if flash
+                .lock(|f| f.borrow_mut().read(offset, &mut header))
+                .is_err()
+            {
+                continue;
+            }
+            if u32::from_le_bytes([header[0], header[1], header[2], header[3]]) != RECORD_MAGIC {
+                continue;
+            }
+            let seq = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            min_seq = Some(min_seq.map_or(seq, |m| m.min(seq)));
+            max_seq = Some(max_seq.map_or(seq, |m| m.max(seq)));
+        }
+
+        let (read_seq, write_seq) = match (min_seq, max_seq) {
+            (Some(min), Some(max)) => (min, max.wrapping_add(1)),
+            _ => (0, 0),
+        };
+
+        FlashRing {
+            flash,
+            base,
+            sector_size,
+            sectors,
+            read_seq,
+            write_seq,
+        }
+    }
+
+    /// The number of buffered records awaiting drain.
+    pub fn len(&self) -> u32 {
+        self.write_seq.wrapping_sub(self.read_seq)
+    }
+
+    /// Whether the ring is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn slot_offset(&self, seq: u32) -> u32 {
+        self.base + (seq % self.sectors) * self.sector_size
+    }
+
+    /// Append a serialized frame, overwriting the oldest record if the ring is
+    /// full. Returns an error only if the flash rejects the write.
+    pub fn append(&mut self, payload: &[u8]) -> Result<(), embassy_stm32::flash::Error> {
+        type Flash = embassy_stm32::flash::Flash<'static, embassy_stm32::flash::Blocking>;
+
+        let len = payload.len().min(MAX_RECORD);
+        let seq = self.write_seq;
+        let offset = self.slot_offset(seq);
+
+        // Assemble the aligned header + payload block and program it in one go.
+        let mut block = [0xFFu8; HEADER_LEN + MAX_RECORD];
+        block[0..4].copy_from_slice(&RECORD_MAGIC.to_le_bytes());
+        block[4..8].copy_from_slice(&seq.to_le_bytes());
+        block[8..12].copy_from_slice(&(len as u32).to_le_bytes());
+        block[HEADER_LEN..HEADER_LEN + len].copy_from_slice(&payload[..len]);
+
+        let write_size = <Flash as NorFlash>::WRITE_SIZE.max(1);
+        let total = ((HEADER_LEN + len) + write_size - 1) / write_size * write_size;
+
+        self.flash.lock(|f| {
+            let mut f = f.borrow_mut();
+            // Erase the slot before rewriting it.
+            f.erase(offset, offset + self.sector_size)?;
+            f.write(offset, &block[..total])
+        })?;
+
+        self.write_seq = self.write_seq.wrapping_add(1);
+        // Drop the oldest record once we exceed capacity.
+        if self.len() > self.sectors {
+            self.read_seq = self.write_seq.wrapping_sub(self.sectors);
+        }
+
+        Ok(())
+    }
+
+    /// Read the oldest buffered frame into `out`, returning its length, or
+    /// `None` if the ring is empty. The slot is erased once it has been read,
+    /// so a reboot does not count the consumed record as buffered again.
+    pub fn pop(&mut self, out: &mut [u8]) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let offset = self.slot_offset(self.read_seq);
+        let mut header = [0u8; HEADER_LEN];
+        self.flash
+            .lock(|f| f.borrow_mut().read(offset, &mut header))
+            .ok()?;
+
+        if u32::from_le_bytes([header[0], header[1], header[2], header[3]]) != RECORD_MAGIC {
+            // Corrupt or missing slot; skip it.
+            self.read_seq = self.read_seq.wrapping_add(1);
+            return None;
+        }
+
+        let len = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        let len = len.min(out.len()).min(MAX_RECORD);
+        self.flash
+            .lock(|f| f.borrow_mut().read(offset + HEADER_LEN as u32, &mut out[..len]))
+            .ok()?;
+
+        // Erase the consumed slot so boot-time recovery does not replay it.
+        self.flash
+            .lock(|f| f.borrow_mut().erase(offset, offset + self.sector_size))
+            .ok();
+
+        self.read_seq = self.read_seq.wrapping_add(1);
+        Some(len)
+    }
+}
+
+/// A ring shared between the sampling tasks.
+pub type SharedRing = Mutex<ThreadModeRawMutex, RefCell<FlashRing>>;
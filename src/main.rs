@@ -6,6 +6,7 @@ use crate::cs1237::{Channel, Config as Cs1237Config, Cs1237, Gain, SamplesPerSec
 use crate::net_util::generate_mac_address;
 use crate::task::TaskSignal;
 use crate::time::{clock_gettime, clock_settime, init_time, Timespec};
+use core::cell::RefCell;
 use core::net::{IpAddr, SocketAddr};
 use core::option::Option::*;
 use core::result::Result::*;
@@ -14,13 +15,17 @@ use embassy_executor::Spawner;
 use embassy_net::tcp::{Error as TcpReadError, TcpSocket};
 use embassy_net::udp::{PacketMetadata, UdpSocket};
 use embassy_net::{Stack, StackResources};
+#[cfg(feature = "eth-stm32")]
 use embassy_stm32::eth::generic_smi::GenericSMI;
+#[cfg(feature = "eth-stm32")]
 use embassy_stm32::eth::{Ethernet, PacketQueue};
-use embassy_stm32::peripherals::ETH;
 use embassy_stm32::rng::Rng;
 use embassy_stm32::time::Hertz;
-use embassy_stm32::{bind_interrupts, eth, peripherals, rng, Config};
+#[cfg(feature = "eth-stm32")]
+use embassy_stm32::eth;
+use embassy_stm32::{bind_interrupts, peripherals, rng, Config};
 use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
 use embassy_sync::signal::Signal as EmbassySignal;
 use embassy_time::{Duration, Timer};
 use embedded_jsonrpc::RpcServer;
@@ -31,17 +36,29 @@ use {defmt_rtt as _, panic_probe as _};
 mod api;
 mod biquad_filter;
 mod cs1237;
+mod edf;
+mod flash;
+mod flash_ring;
+mod monitor;
+mod mqtt;
+mod net;
 mod net_util;
 mod ncpt;
+mod scpi;
+mod status;
 mod task;
 mod time;
 
 bind_interrupts!(struct Irqs {
-    ETH => eth::InterruptHandler;
     RNG => rng::InterruptHandler<peripherals::RNG>;
 });
 
-type Device = Ethernet<'static, ETH, GenericSMI>;
+#[cfg(feature = "eth-stm32")]
+bind_interrupts!(struct EthIrqs {
+    ETH => eth::InterruptHandler;
+});
+
+use crate::net::Device;
 
 const NTP_PORT: u16 = 123;
 const NTP_PACKET_SIZE: usize = 48;
@@ -51,6 +68,8 @@ async fn net_task(mut runner: embassy_net::Runner<'static, Device>) -> ! {
     runner.run().await
 }
 
+/// NTP client mode: periodically query the DHCP gateway and discipline the RTC.
+#[cfg(not(feature = "ntp-server"))]
 #[embassy_executor::task]
 async fn timesync_task(stack: Stack<'static>) -> ! {
     let timestamp_gen = TimestampGen::default();
@@ -99,6 +118,81 @@ async fn timesync_task(stack: Stack<'static>) -> ! {
     }
 }
 
+/// NTP server mode: answer inbound SNTP requests on port 123 from this unit's
+/// own RTC, so an isolated cluster of sensors can align their sample
+/// timestamps to one master with no upstream internet NTP.
+#[cfg(feature = "ntp-server")]
+#[embassy_executor::task]
+async fn timesync_task(stack: Stack<'static>) -> ! {
+    /// Seconds between the NTP epoch (1900-01-01) and the unix epoch.
+    const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+    // Encode a timespec as a 64-bit NTP timestamp (32.32 fixed point).
+    fn ntp_timestamp(tp: &Timespec) -> [u8; 8] {
+        let secs = (tp.seconds + NTP_UNIX_OFFSET) as u32;
+        let frac = (((tp.micros as u64) << 32) / 1_000_000) as u32;
+        let mut bytes = [0u8; 8];
+        bytes[0..4].copy_from_slice(&secs.to_be_bytes());
+        bytes[4..8].copy_from_slice(&frac.to_be_bytes());
+        bytes
+    }
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; NTP_PACKET_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; NTP_PACKET_SIZE];
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(NTP_PORT).unwrap();
+
+    let mut request = [0u8; NTP_PACKET_SIZE];
+    loop {
+        let (n, endpoint) = match socket.recv_from(&mut request).await {
+            Ok(recv) => recv,
+            Err(err) => {
+                error!("NTP receive error: {:?}", err);
+                continue;
+            }
+        };
+
+        if n < NTP_PACKET_SIZE {
+            warn!("Ignoring short NTP request ({} bytes)", n);
+            continue;
+        }
+
+        // Stamp the receive time as soon as the request has arrived.
+        let recv_time = clock_gettime().unwrap();
+
+        let mut reply = [0u8; NTP_PACKET_SIZE];
+        // LI = 0, VN = 4, Mode = 4 (server).
+        reply[0] = 0b00_100_100;
+        reply[1] = 1; // Stratum 1 (primary reference).
+        reply[2] = request[2]; // Echo the client's poll interval.
+        reply[3] = 0xEC; // Precision ~= 2^-20 s.
+        reply[12..16].copy_from_slice(b"LOCL"); // Reference identifier.
+
+        let reference = ntp_timestamp(&recv_time);
+        reply[16..24].copy_from_slice(&reference); // Reference timestamp.
+        reply[24..32].copy_from_slice(&request[40..48]); // Originate = client transmit.
+        reply[32..40].copy_from_slice(&ntp_timestamp(&recv_time)); // Receive timestamp.
+
+        let transmit = clock_gettime().unwrap();
+        reply[40..48].copy_from_slice(&ntp_timestamp(&transmit)); // Transmit timestamp.
+
+        if let Err(err) = socket.send_to(&reply, endpoint).await {
+            error!("NTP reply error: {:?}", err);
+        } else {
+            debug!("Served NTP request from {:?}", endpoint);
+        }
+    }
+}
+
 #[embassy_executor::main]
 async fn main(spawner: Spawner) -> ! {
     debug!("Starting...");
@@ -161,27 +255,86 @@ async fn main(spawner: Spawner) -> ! {
     debug!("Initializing Ethernet device...");
     let mac_addr = generate_mac_address();
 
-    static PACKETS: StaticCell<PacketQueue<4, 4>> = StaticCell::new();
-    let packet_queue = PACKETS.init(PacketQueue::<4, 4>::new());
-
     debug!("Bringing up Ethernet device...");
 
-    let device = Ethernet::new(
-        packet_queue,
-        p.ETH,
-        Irqs,
-        p.PA1,
-        p.PA2,
-        p.PC1,
-        p.PA7,
-        p.PC4,
-        p.PC5,
-        p.PB12,
-        p.PB13,
-        p.PB11,
-        GenericSMI::new(1), // The default dp83848 address is "1" unlike the LAN8742 which is "0".
-        mac_addr,
-    );
+    // On-chip MAC + external RMII PHY (STM32 with native Ethernet).
+    #[cfg(feature = "eth-stm32")]
+    let device = {
+        static PACKETS: StaticCell<PacketQueue<4, 4>> = StaticCell::new();
+        let packet_queue = PACKETS.init(PacketQueue::<4, 4>::new());
+
+        Ethernet::new(
+            packet_queue,
+            p.ETH,
+            EthIrqs,
+            p.PA1,
+            p.PA2,
+            p.PC1,
+            p.PA7,
+            p.PC4,
+            p.PC5,
+            p.PB12,
+            p.PB13,
+            p.PB11,
+            GenericSMI::new(1), // The default dp83848 address is "1" unlike the LAN8742 which is "0".
+            mac_addr,
+        )
+    };
+
+    // SPI-attached WIZnet W5500 driven over SPI2.
+    #[cfg(feature = "eth-w5500")]
+    let device = {
+        use embassy_stm32::gpio::{Level, Output, Pull, Speed};
+        use embassy_stm32::exti::ExtiInput;
+        use embassy_stm32::gpio::Input;
+        use embassy_stm32::spi::{Config as SpiConfig, Spi};
+        use embedded_hal_bus::spi::ExclusiveDevice;
+
+        let spi = Spi::new(
+            p.SPI2, p.PB13, p.PB15, p.PB14, p.DMA1_CH4, p.DMA1_CH3, SpiConfig::default(),
+        );
+        let cs = Output::new(p.PB12, Level::High, Speed::VeryHigh);
+        let spi_dev = ExclusiveDevice::new(spi, cs, embassy_time::Delay).unwrap();
+
+        let int = ExtiInput::new(Input::new(p.PC6, Pull::Up), p.EXTI6);
+        let reset = Output::new(p.PC7, Level::High, Speed::Low);
+
+        static STATE: StaticCell<embassy_net_wiznet::State<8, 8>> = StaticCell::new();
+        let state = STATE.init(embassy_net_wiznet::State::new());
+
+        let (device, runner) = embassy_net_wiznet::new(
+            mac_addr,
+            state,
+            embassy_net_wiznet::chip::W5500,
+            spi_dev,
+            int,
+            reset,
+        )
+        .await;
+        spawner.spawn(net::wiznet_runner(runner)).unwrap();
+        device
+    };
+
+    // SPI-attached Microchip ENC28J60 driven over SPI2.
+    #[cfg(feature = "eth-enc28j60")]
+    let device = {
+        use embassy_stm32::gpio::{Level, Output, Speed};
+        use embassy_stm32::spi::{Config as SpiConfig, Spi};
+        use embedded_hal_bus::spi::ExclusiveDevice;
+
+        let spi = Spi::new(
+            p.SPI2, p.PB13, p.PB15, p.PB14, p.DMA1_CH4, p.DMA1_CH3, SpiConfig::default(),
+        );
+        let cs = Output::new(p.PB12, Level::High, Speed::VeryHigh);
+        let spi_dev = ExclusiveDevice::new(spi, cs, embassy_time::Delay).unwrap();
+
+        static STATE: StaticCell<embassy_net_enc28j60::State<8, 8>> = StaticCell::new();
+        let state = STATE.init(embassy_net_enc28j60::State::new());
+
+        let (device, runner) = embassy_net_enc28j60::new(state, spi_dev, mac_addr);
+        spawner.spawn(net::enc_runner(runner)).unwrap();
+        device
+    };
 
     // Acquire network configuration using DHCP.
     let config = embassy_net::Config::dhcpv4(embassy_net::DhcpConfig::default());
@@ -200,6 +353,9 @@ async fn main(spawner: Spawner) -> ! {
     // Begin synchronizing time with NTP server.
     spawner.spawn(timesync_task(stack)).unwrap();
 
+    // Serve the SCPI control/query interface on its own port.
+    spawner.spawn(scpi::serve(stack)).unwrap();
+
     // Create JSON-RPC server
     static RPC_SERVER: StaticCell<RpcServer<'static, TcpReadError>> = StaticCell::new();
     let rpc_server = RPC_SERVER.init_with(RpcServer::new);
@@ -211,21 +367,83 @@ async fn main(spawner: Spawner) -> ! {
     let ncpt_sampling_task_signals =
         NCPT_SAMPLING_TASK_SIGNALS.init_with(EmbassySignal::new);
 
+    // Signal registry. Each descriptor pairs a signal with its sampling task's
+    // start/stop channel; add more entries here as channels come online.
+    static REGISTRY: StaticCell<[api::SignalDescriptor; 1]> = StaticCell::new();
+    let registry: &'static [api::SignalDescriptor] =
+        REGISTRY.init([api::SignalDescriptor::nasal_pressure(ncpt_sampling_task_signals)]);
+
+    // The internal flash is shared between the store-and-forward ring and the
+    // EDF+ recorder, so it is wrapped once and handed to each by reference;
+    // each owns a disjoint offset region.
+    static FLASH: StaticCell<flash::SharedFlash> = StaticCell::new();
+    let shared_flash = FLASH.init(BlockingMutex::new(RefCell::new(
+        embassy_stm32::flash::Flash::new_blocking(p.FLASH),
+    )));
+
+    // Store-and-forward ring used to buffer notification frames while the
+    // JSON-RPC client is disconnected. The reserved 128 KiB region is split
+    // into slots of `SLOT_MIN` rounded up to a whole erase sector, one frame
+    // per slot, and the oldest frames are overwritten once the ring fills.
+    // Where one erase sector spans the whole 128 KiB region, the ring holds a
+    // single frame and every append erases the whole region.
+    use embedded_storage::nor_flash::NorFlash;
+    const FLASH_RING_BASE: u32 = 0x0C0000;
+    const FLASH_RING_SIZE: u32 = 128 * 1024;
+    const FLASH_RING_ERASE: u32 = <flash::Flash as NorFlash>::ERASE_SIZE as u32;
+    const FLASH_RING_SECTOR: u32 =
+        (flash_ring::SLOT_MIN as u32).div_ceil(FLASH_RING_ERASE) * FLASH_RING_ERASE;
+    const FLASH_RING_SECTORS: u32 = FLASH_RING_SIZE / FLASH_RING_SECTOR;
+    static FLASH_RING: StaticCell<flash_ring::SharedRing> = StaticCell::new();
+    let flash_ring = FLASH_RING.init(BlockingMutex::new(RefCell::new(flash_ring::FlashRing::new(
+        shared_flash,
+        FLASH_RING_BASE,
+        FLASH_RING_SECTOR,
+        FLASH_RING_SECTORS,
+    ))));
+
+    // On-device EDF+ recording region, the remaining top flash sector.
+    const EDF_BASE: u32 = 0x0E0000;
+    const EDF_CAPACITY: u32 = 128 * 1024;
+    static RECORDER: StaticCell<edf::SharedRecorder> = StaticCell::new();
+    let recorder = RECORDER.init(BlockingMutex::new(RefCell::new(edf::EdfRecorder::new(
+        shared_flash,
+        EDF_BASE,
+        EDF_CAPACITY,
+    ))));
+
     // Register handlers.
     static RPC_HANDLER: StaticCell<RpcHandler> = StaticCell::new();
-    let rpc_handler =
-        RPC_HANDLER.init_with(|| RpcHandler::new(ncpt_sampling_task_signals));
+    let rpc_handler = RPC_HANDLER.init_with(|| RpcHandler::new(registry, recorder));
 
     rpc_server
         .register_handler("openpsg.*", rpc_handler)
         .unwrap();
 
+    // Telemetry channel feeding the outbound MQTT publisher.
+    static TELEMETRY_CHANNEL: StaticCell<mqtt::TelemetryChannel> = StaticCell::new();
+    let telemetry_channel = TELEMETRY_CHANNEL.init_with(mqtt::TelemetryChannel::new);
+
+    // Launch the MQTT telemetry push task.
+    spawner
+        .spawn(mqtt::telemetry(stack, telemetry_channel, mac_addr))
+        .unwrap();
+
+    // Launch the periodic heartbeat/status task.
+    spawner.spawn(status::heartbeat(rpc_server)).unwrap();
+
+    // Launch the internal-rail monitoring task (supply, VBAT, core temp).
+    spawner.spawn(monitor::monitor(p.ADC1)).unwrap();
+
     // Launch pressure transducer sampling task.
     spawner
         .spawn(ncpt::sample(
             rpc_server,
-            ncpt_sampling_task_signals,
+            &registry[0],
             ncpt_adc,
+            telemetry_channel,
+            flash_ring,
+            recorder,
         ))
         .unwrap();
 
@@ -256,12 +474,14 @@ async fn main(spawner: Spawner) -> ! {
     }
 }
 
+#[cfg(not(feature = "ntp-server"))]
 #[derive(Clone, Copy, Default)]
 struct TimestampGen {
     now: u64,
     now_micros: u32,
 }
 
+#[cfg(not(feature = "ntp-server"))]
 impl sntpc::NtpTimestampGenerator for TimestampGen {
     fn init(&mut self) {
         let tp = clock_gettime().unwrap();
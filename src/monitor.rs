@@ -0,0 +1,96 @@
+//! Low-rate monitoring of the STM32's internal ADC rails.
+//!
+//! The pressure front-end depends on a stable analog supply, so a sagging
+//! battery or thermal drift quietly biases the CS1237 readings before it ever
+//! corrupts a recording. This task periodically reads the internal voltage
+//! reference, temperature sensor, and backup-battery divider in a single
+//! oneshot pass, converts the raw counts to millivolts and milli-degrees using
+//! the factory calibration values, and publishes them in the heartbeat so the
+//! host can warn the operator in time.
+
+use defmt::debug;
+use embassy_stm32::adc::{Adc, SampleTime};
+use embassy_stm32::peripherals::ADC1;
+use embassy_time::{Duration, Timer};
+
+/// The supply voltage at which the factory calibration values were taken, in
+/// millivolts (VREFINT_CAL / TS_CAL* are measured at VDDA = 3.3 V).
+const VREF_CAL_MV: u32 = 3300;
+
+/// Factory calibration of the internal reference, sampled at `VREF_CAL_MV`.
+/// (STM32F4 reference manual, system-memory calibration addresses.)
+const VREFINT_CAL: *const u16 = 0x1FFF_7A2A as *const u16;
+/// Temperature-sensor calibration sampled at 30 °C.
+const TS_CAL1: *const u16 = 0x1FFF_7A2C as *const u16;
+/// Temperature-sensor calibration sampled at 110 °C.
+const TS_CAL2: *const u16 = 0x1FFF_7A2E as *const u16;
+/// The two temperature-calibration points, in degrees Celsius.
+const TS_CAL1_TEMP: i32 = 30;
+const TS_CAL2_TEMP: i32 = 110;
+
+/// The VBAT pin is internally divided by 4 on this part before reaching the
+/// ADC, so the converted voltage must be scaled back up.
+const VBAT_DIVIDER: u32 = 4;
+
+/// How often the rails are sampled.
+const MONITOR_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Periodically sample the internal reference, temperature sensor, and VBAT
+/// divider, convert them to physical units, and publish them via
+/// [`crate::status::set_rails`].
+#[embassy_executor::task]
+pub async fn monitor(adc1: ADC1) -> ! {
+    let mut adc = Adc::new(adc1);
+    // The internal channels need a long sampling window to settle.
+    adc.set_sample_time(SampleTime::CYCLES480);
+
+    let mut vrefint = adc.enable_vrefint();
+    let mut temperature = adc.enable_temperature();
+    let mut vbat = adc.enable_vbat();
+
+    // Factory calibration is programmed once at manufacture; read it up front.
+    let vrefint_cal = unsafe { core::ptr::read_volatile(VREFINT_CAL) } as u32;
+    let ts_cal1 = unsafe { core::ptr::read_volatile(TS_CAL1) } as i32;
+    let ts_cal2 = unsafe { core::ptr::read_volatile(TS_CAL2) } as i32;
+
+    loop {
+        // Derive the true analog supply from the reference: VDDA scales
+        // inversely with the measured reference count.
+        let vrefint_raw = adc.read(&mut vrefint) as u32;
+        let supply_mv = if vrefint_raw == 0 {
+            0
+        } else {
+            VREF_CAL_MV * vrefint_cal / vrefint_raw
+        };
+
+        // Express every other channel in terms of the supply we just computed.
+        let to_millivolts = |raw: u32| raw * supply_mv / 4095;
+
+        let vbat_raw = adc.read(&mut vbat) as u32;
+        let vbat_mv = to_millivolts(vbat_raw) * VBAT_DIVIDER;
+
+        // Two-point interpolation between the factory temperature calibration
+        // points, referenced to the calibration supply.
+        let ts_raw = adc.read(&mut temperature) as i32;
+        let ts_raw = if supply_mv == 0 {
+            ts_raw
+        } else {
+            ts_raw * supply_mv as i32 / VREF_CAL_MV as i32
+        };
+        let span = ts_cal2 - ts_cal1;
+        let core_temp_millic = if span == 0 {
+            0
+        } else {
+            TS_CAL1_TEMP * 1000
+                + (ts_raw - ts_cal1) * (TS_CAL2_TEMP - TS_CAL1_TEMP) * 1000 / span
+        };
+
+        debug!(
+            "Rails: VDDA {} mV, VBAT {} mV, core {} m°C",
+            supply_mv, vbat_mv, core_temp_millic
+        );
+        crate::status::set_rails(supply_mv, vbat_mv, core_temp_millic);
+
+        Timer::after(MONITOR_INTERVAL).await;
+    }
+}
@@ -0,0 +1,218 @@
+use crate::api::SignalValues;
+use core::net::{IpAddr, SocketAddr};
+use defmt::{debug, info, warn};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Timer};
+use heapless::{String, Vec};
+use rust_mqtt::client::client::MqttClient;
+use rust_mqtt::client::client_config::{ClientConfig, MqttVersion};
+use rust_mqtt::packet::v5::publish_packet::QualityOfService;
+use rust_mqtt::packet::v5::reason_codes::ReasonCode;
+use rust_mqtt::utils::rng_generator::CountingRng;
+
+/// The TCP port of the MQTT broker.
+const MQTT_BROKER_PORT: u16 = 1883;
+
+/// The MQTT keepalive interval, in seconds. A PINGREQ is sent automatically by
+/// the client library when no other traffic has been sent for this long.
+const MQTT_KEEPALIVE_SECS: u16 = 60;
+
+/// The hostname or literal IP of the MQTT broker. Overridable at build time via
+/// `MQTT_BROKER_HOST`; defaults so an unconfigured build still compiles.
+const MQTT_BROKER_HOST: &str = match option_env!("MQTT_BROKER_HOST") {
+    Some(host) => host,
+    None => "localhost",
+};
+
+/// The quality of service used when publishing telemetry frames. Set
+/// `MQTT_QOS=0` at build time for fire-and-forget QoS0; anything else (the
+/// default) uses acknowledged QoS1.
+const MQTT_QOS: QualityOfService = match option_env!("MQTT_QOS") {
+    Some("0") => QualityOfService::QoS0,
+    _ => QualityOfService::QoS1,
+};
+
+/// The largest number of samples we will ever batch into a single frame,
+/// matching the sampler's per-frame bound. At sample rates above 256 SPS a
+/// frame holds less than one second of samples.
+const MAX_FRAME_SAMPLES: usize = 256;
+
+/// The depth of the telemetry queue. Frames are buffered here while the broker
+/// is unreachable so the sampling task never blocks waiting on the network.
+const TELEMETRY_QUEUE_DEPTH: usize = 8;
+
+/// A single batch of NTP-timestamped samples awaiting publication.
+pub struct TelemetryFrame {
+    /// The unique identifier of the signal these samples belong to.
+    pub id: u32,
+    /// The start timestamp of the batch (seconds since the unix epoch).
+    pub seconds: u64,
+    /// The fractional part of the start timestamp, in microseconds.
+    pub micros: u32,
+    /// The scaled EDF samples.
+    pub values: Vec<i16, MAX_FRAME_SAMPLES>,
+}
+
+/// The channel over which the sampling tasks hand frames to the telemetry task.
+pub type TelemetryChannel = Channel<ThreadModeRawMutex, TelemetryFrame, TELEMETRY_QUEUE_DEPTH>;
+
+/// Derive the per-device topic (`openpsg/<mac>/values`) from the MAC address.
+fn device_topic(mac: [u8; 6]) -> String<32> {
+    let mut topic: String<32> = String::new();
+    core::fmt::write(
+        &mut topic,
+        format_args!(
+            "openpsg/{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}/values",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        ),
+    )
+    .unwrap();
+    topic
+}
+
+/// Resolve the configured broker address, preferring a literal IP and falling
+/// back to a DNS lookup of the `MQTT_BROKER_HOST` build-time host.
+async fn resolve_broker(stack: Stack<'static>) -> Option<SocketAddr> {
+    let host = MQTT_BROKER_HOST;
+    if let Ok(addr) = host.parse::<IpAddr>() {
+        return Some(SocketAddr::new(addr, MQTT_BROKER_PORT));
+    }
+
+    match stack.dns_query(host, DnsQueryType::A).await {
+        Ok(addrs) => addrs
+            .first()
+            .map(|addr| SocketAddr::new((*addr).into(), MQTT_BROKER_PORT)),
+        Err(e) => {
+            warn!("MQTT broker DNS lookup failed: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Publish batched telemetry frames to the configured MQTT broker, reconnecting
+/// with exponential backoff whenever the link or broker drops. Frames continue
+/// to buffer in `channel` while disconnected rather than blocking the samplers.
+#[embassy_executor::task]
+pub async fn telemetry(
+    stack: Stack<'static>,
+    channel: &'static TelemetryChannel,
+    mac: [u8; 6],
+) -> ! {
+    let topic = device_topic(mac);
+    let mut backoff = Duration::from_secs(1);
+
+    // A frame that was popped from the channel but not yet acknowledged by the
+    // broker. `channel.receive()` removes the frame, so on a reconnect it is
+    // held here and retried before any newer frame rather than being lost.
+    let mut pending: Option<TelemetryFrame> = None;
+
+    loop {
+        let Some(broker) = resolve_broker(stack).await else {
+            Timer::after(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_secs(30));
+            continue;
+        };
+
+        let mut rx_buffer = [0u8; 1024];
+        let mut tx_buffer = [0u8; 1460];
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        socket.set_keep_alive(Some(Duration::from_secs(5)));
+
+        if let Err(e) = socket.connect(broker).await {
+            warn!("MQTT connect error: {:?}", e);
+            Timer::after(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_secs(30));
+            continue;
+        }
+
+        info!("Connected to MQTT broker {:?}", broker);
+
+        let mut config: ClientConfig<5, CountingRng> =
+            ClientConfig::new(MqttVersion::MQTTv5, CountingRng(0));
+        config.keep_alive = MQTT_KEEPALIVE_SECS;
+        config.add_client_id(&client_id(mac));
+
+        let mut recv_buffer = [0u8; 512];
+        let mut write_buffer = [0u8; 1460];
+        let mut client = MqttClient::new(
+            socket,
+            &mut write_buffer,
+            write_buffer.len(),
+            &mut recv_buffer,
+            recv_buffer.len(),
+            config,
+        );
+
+        if let Err(e) = client.connect_to_broker().await {
+            warn!("MQTT broker handshake error: {:?}", e);
+            Timer::after(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_secs(30));
+            continue;
+        }
+
+        // Link is healthy again; reset the backoff.
+        backoff = Duration::from_secs(1);
+
+        // Drain frames until the connection errors, at which point we break out
+        // to reconnect. A frame that fails to publish is stashed in `pending`
+        // and retried first after the next successful reconnect.
+        loop {
+            let frame = match pending.take() {
+                Some(frame) => frame,
+                None => channel.receive().await,
+            };
+            let mut payload = [0u8; 1460];
+            let payload_len = match encode_frame(&frame, &mut payload) {
+                Some(len) => len,
+                None => {
+                    warn!("Dropping oversized telemetry frame");
+                    continue;
+                }
+            };
+
+            match client
+                .send_message(&topic, &payload[..payload_len], MQTT_QOS, false)
+                .await
+            {
+                Ok(()) => debug!("Published {} telemetry bytes", payload_len),
+                Err(ReasonCode::NetworkError) | Err(ReasonCode::Timeout) => {
+                    warn!("MQTT publish failed, reconnecting");
+                    pending = Some(frame);
+                    break;
+                }
+                Err(e) => warn!("MQTT publish error: {:?}", e),
+            }
+        }
+    }
+}
+
+/// Derive a stable client identifier (`openpsg-<mac>`) from the MAC address.
+fn client_id(mac: [u8; 6]) -> String<32> {
+    let mut id: String<32> = String::new();
+    core::fmt::write(
+        &mut id,
+        format_args!(
+            "openpsg-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+            mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        ),
+    )
+    .unwrap();
+    id
+}
+
+/// Serialize a telemetry frame into the same `SignalValues` JSON wire format
+/// used by the `openpsg.values` JSON-RPC notification.
+fn encode_frame(frame: &TelemetryFrame, out: &mut [u8]) -> Option<usize> {
+    let payload = SignalValues {
+        id: frame.id,
+        timestamp: rfc3339::format_unix(frame.seconds, frame.micros),
+        values: &frame.values,
+    };
+
+    serde_json_core::to_slice(&payload, out).ok()
+}
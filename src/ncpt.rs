@@ -1,83 +1,104 @@
-use crate::api::SignalValues;
+use crate::api::{SignalDescriptor, SignalValues, MAX_FILTERS};
 use crate::biquad_filter::BiquadFilter;
 use crate::cs1237::Cs1237;
+use crate::edf::SharedRecorder;
+use crate::flash_ring::{SharedRing, MAX_RECORD};
+use crate::mqtt::{TelemetryChannel, TelemetryFrame};
 use crate::task::TaskSignal;
 use crate::time::clock_gettime;
-use defmt::error;
+use defmt::{error, warn};
 use embassy_futures::select::{select, Either};
 use embassy_net::tcp::Error as TcpReadError;
-use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
-use embassy_sync::signal::Signal;
 use embedded_jsonrpc::{RpcRequest, RpcServer, JSONRPC_VERSION};
 use heapless::Vec;
 
 /// The signal ID for the pressure transducer.
 pub(crate) const NCPT_SIGNAL_ID: u32 = 1;
 
-const SAMPLES_PER_SECOND: usize = 40;
-
-// Filter out DC offset, params f0=0.1Hz. This will take ~10s to settle.
-const DC_REJECTION_HIGHPASS_FILTER_0_1HZ_NUMERATOR: [f32; 3] = [0.98895425, -1.9779085,   0.98895425];
-const DC_REJECTION_HIGHPASS_FILTER_0_1HZ_DENOMINATOR: [f32; 3] = [1.0, -1.97778648, 0.97803051];
-
-// There seems to be some aliasing of mains hum (50Hz) at ~4Hz, this will likely
-// need a USA version. Params f0=4.5Hz, Q=0.5
-const ANTIALIAS_NOTCH_FILTER_4HZ_NUMERATOR: [f32; 3] = [0.53935085, -0.82025121, 0.53935085];
-const ANTIALIAS_NOTCH_FILTER_4HZ_DENOMINATOR: [f32; 3] = [1.0, -0.82025121, 0.07870171];
+/// The largest per-second sample buffer any signal may use. Sized for the
+/// highest CS1237 sample rate a descriptor is likely to request.
+const MAX_SAMPLES_PER_SECOND: usize = 256;
 
 const I24_MAX: i32 = 8_388_607;
 
-#[embassy_executor::task]
+/// Sample a single signal described by `descriptor`, filtering, scaling into
+/// the EDF int16 format, and streaming one-second frames over JSON-RPC and
+/// MQTT. The signal's rate, range, scaling, and filter chain all come from the
+/// descriptor rather than being baked in.
+#[embassy_executor::task(pool_size = crate::api::MAX_SIGNALS)]
 pub async fn sample(
     rpc_server: &'static RpcServer<'static, TcpReadError>,
-    signals: &'static Signal<ThreadModeRawMutex, TaskSignal>,
+    descriptor: &'static SignalDescriptor,
     mut adc: Cs1237<'static>,
+    telemetry: &'static TelemetryChannel,
+    ring: &'static SharedRing,
+    recorder: &'static SharedRecorder,
 ) -> ! {
-    let mut dc_rejection_filter: BiquadFilter<i32> = BiquadFilter::new(
-        DC_REJECTION_HIGHPASS_FILTER_0_1HZ_NUMERATOR,
-        DC_REJECTION_HIGHPASS_FILTER_0_1HZ_DENOMINATOR,
-    );
+    // Build the prefiltering chain declared by the descriptor, so the reported
+    // and applied filtering stay in lock-step.
+    let mut filters: Vec<BiquadFilter<i32>, MAX_FILTERS> = Vec::new();
+    for filter in &descriptor.filters {
+        if let Some(biquad) = filter.to_biquad::<i32>(descriptor.sample_rate) {
+            filters.push(biquad).ok();
+        } else {
+            warn!("Skipping unrealizable filter for signal {}", descriptor.id);
+        }
+    }
 
-    let mut antialias_filter: BiquadFilter<i32> = BiquadFilter::new(
-        ANTIALIAS_NOTCH_FILTER_4HZ_NUMERATOR,
-        ANTIALIAS_NOTCH_FILTER_4HZ_DENOMINATOR,
-    );
+    let samples_per_second = (descriptor.sample_rate as usize).min(MAX_SAMPLES_PER_SECOND);
 
     loop {
         // Wait for the start signal
-        while signals.wait().await != TaskSignal::Start {}
+        while descriptor.signal.wait().await != TaskSignal::Start {}
+        crate::status::set_signal_active(descriptor.id, true);
 
         // One second of sample data.
-        let mut samples = Vec::<i32, SAMPLES_PER_SECOND>::new();
+        let mut samples = Vec::<i32, MAX_SAMPLES_PER_SECOND>::new();
         let mut samples_start = clock_gettime().unwrap();
-        let mut scaled_samples = [0; SAMPLES_PER_SECOND];
         loop {
-            match select(adc.read(), signals.wait()).await {
+            match select(adc.read(), descriptor.signal.wait()).await {
                 Either::First(Ok(value)) => {
-                    samples.push(value).unwrap();
+                    samples.push(value).ok();
 
-                    if samples.is_full() {
-                        // Filter out the DC offset.
-                        dc_rejection_filter.apply(samples.as_mut_slice());
-                        // Filter out the mains hum alias.
-                        antialias_filter.apply(samples.as_mut_slice());
+                    if samples.len() >= samples_per_second {
+                        // Apply the descriptor's prefiltering chain in series.
+                        for filter in filters.iter_mut() {
+                            filter.apply(samples.as_mut_slice());
+                        }
 
-                        // Scale the samples into a 16bit value representing the range -200Pa to 200Pa.
-                        // This is the EDF sample value format.
-                        for (i, sample) in samples.iter().enumerate() {
-                            // 10.4KPa is the full scale range of the pressure transducer 
-                            // at this gain setting.
-                            let mut pressure_pa: f32 = 10_400.0 * (*sample as f32 / I24_MAX as f32);
+                        // Scale the samples into a 16bit value over the signal's
+                        // range. This is the EDF sample value format.
+                        let mut scaled_samples: Vec<i16, MAX_SAMPLES_PER_SECOND> = Vec::new();
+                        for sample in samples.iter() {
+                            let mut value =
+                                descriptor.full_scale * (*sample as f32 / I24_MAX as f32);
 
-                            // Clamp the pressure to the range -200Pa to 200Pa.
-                            pressure_pa = pressure_pa.max(-200.0).min(200.0);
+                            // Clamp to the signal's physical range.
+                            value = value.max(descriptor.min).min(descriptor.max);
+
+                            // Expose the latest pressure reading to the SCPI
+                            // interface.
+                            if descriptor.id == NCPT_SIGNAL_ID {
+                                crate::scpi::set_latest_pressure(value);
+                            }
 
                             // Scale to a 16bit signed integer (for EDF).
-                            scaled_samples[i] = ((pressure_pa / 200.0) * i16::MAX as f32) as i16;
+                            scaled_samples
+                                .push(((value / descriptor.max) * i16::MAX as f32) as i16)
+                                .ok();
                         }
 
+                        // Persist this epoch to the on-device EDF+ recording
+                        // when one is in progress.
+                        recorder.lock(|r| {
+                            let mut r = r.borrow_mut();
+                            if r.is_recording() {
+                                r.append_signal(descriptor.id, &scaled_samples);
+                            }
+                        });
+
                         let notification_payload = &SignalValues {
-                            id: NCPT_SIGNAL_ID,
+                            id: descriptor.id,
                             timestamp: rfc3339::format_unix(
                                 samples_start.seconds,
                                 samples_start.micros,
@@ -97,10 +118,53 @@ pub async fn sample(
                             serde_json_core::to_slice(&notification, &mut notification_json)
                                 .unwrap();
 
-                        rpc_server
+                        // If frames are still buffered from an earlier
+                        // disconnect, enqueue this live frame behind them and
+                        // drain the whole ring oldest-first, so the recovered
+                        // gap is delivered in order ahead of live streaming.
+                        // Otherwise stream live, buffering to flash on error
+                        // rather than panicking.
+                        let backlogged = ring.lock(|r| !r.borrow().is_empty());
+                        if backlogged {
+                            ring.lock(|r| {
+                                if r.borrow_mut()
+                                    .append(&notification_json[..notification_len])
+                                    .is_err()
+                                {
+                                    error!("Failed to buffer frame to flash");
+                                }
+                            });
+                            drain_backlog(rpc_server, ring).await;
+                        } else if let Err(e) = rpc_server
                             .notify(&notification_json[..notification_len])
                             .await
-                            .unwrap();
+                        {
+                            warn!("Notify failed, buffering to flash: {:?}", e);
+                            ring.lock(|r| {
+                                if r.borrow_mut()
+                                    .append(&notification_json[..notification_len])
+                                    .is_err()
+                                {
+                                    error!("Failed to buffer frame to flash");
+                                }
+                            });
+                        }
+
+                        // Hand the same batch to the MQTT publisher. Use a
+                        // non-blocking send so a stalled broker or a full queue
+                        // never backs up the sampling loop; the telemetry task
+                        // buffers and retries on its own schedule.
+                        let mut frame = TelemetryFrame {
+                            id: descriptor.id,
+                            seconds: samples_start.seconds,
+                            micros: samples_start.micros,
+                            values: Vec::new(),
+                        };
+                        frame.values.extend_from_slice(&scaled_samples).ok();
+                        if telemetry.try_send(frame).is_err() {
+                            warn!("Telemetry queue full, dropping frame");
+                            crate::status::record_dropped_frame();
+                        }
 
                         samples_start = clock_gettime().unwrap();
                         samples.clear();
@@ -108,6 +172,7 @@ pub async fn sample(
                 }
                 Either::First(Err(e)) => {
                     error!("Error reading from ADC: {:?}", e);
+                    crate::status::record_adc_error();
                     break;
                 }
                 Either::Second(sig) => match sig {
@@ -116,5 +181,32 @@ pub async fn sample(
                 },
             }
         }
+
+        crate::status::set_signal_active(descriptor.id, false);
+    }
+}
+
+/// Replay buffered frames oldest-first over the reconnected link, stopping as
+/// soon as a send fails so the remaining backlog stays buffered for next time.
+/// The frame whose send failed is re-appended at the tail of the ring, so it is
+/// replayed after the frames that were still buffered behind it.
+async fn drain_backlog(
+    rpc_server: &'static RpcServer<'static, TcpReadError>,
+    ring: &'static SharedRing,
+) {
+    let mut frame = [0u8; MAX_RECORD];
+    loop {
+        let len = match ring.lock(|r| r.borrow_mut().pop(&mut frame)) {
+            Some(len) => len,
+            None => break,
+        };
+
+        if rpc_server.notify(&frame[..len]).await.is_err() {
+            // Link dropped again; re-buffer this frame and stop draining.
+            ring.lock(|r| {
+                r.borrow_mut().append(&frame[..len]).ok();
+            });
+            break;
+        }
     }
 }
@@ -0,0 +1,60 @@
+//! Network device abstraction.
+//!
+//! `main()` drives [`embassy_net::new`] over a single [`Device`] type. Which
+//! concrete driver that alias resolves to is selected at compile time so the
+//! same firmware can target either an STM32 part with an on-chip MAC and an
+//! external RMII PHY (the default) or a cheap MCU with an SPI-attached Ethernet
+//! controller (WIZnet W5500 or Microchip ENC28J60), both of which expose an
+//! [`embassy_net_driver_channel`] device. The DHCP/NTP/JSON-RPC setup layered
+//! on top of the returned [`Stack`](embassy_net::Stack) is identical in all
+//! three cases.
+
+#[cfg(feature = "eth-stm32")]
+use embassy_stm32::eth::generic_smi::GenericSMI;
+#[cfg(feature = "eth-stm32")]
+use embassy_stm32::eth::Ethernet;
+#[cfg(feature = "eth-stm32")]
+use embassy_stm32::peripherals::ETH;
+
+/// The maximum transmission unit used by the SPI-attached backends.
+#[cfg(any(feature = "eth-w5500", feature = "eth-enc28j60"))]
+const MTU: usize = 1514;
+
+/// The network device this firmware build drives.
+#[cfg(feature = "eth-stm32")]
+pub type Device = Ethernet<'static, ETH, GenericSMI>;
+
+/// The network device this firmware build drives.
+#[cfg(any(feature = "eth-w5500", feature = "eth-enc28j60"))]
+pub type Device = embassy_net_driver_channel::Device<'static, MTU>;
+
+/// Drive the WIZnet W5500's internal state machine and SPI traffic.
+#[cfg(feature = "eth-w5500")]
+#[embassy_executor::task]
+pub async fn wiznet_runner(
+    runner: embassy_net_wiznet::Runner<
+        'static,
+        embedded_hal_bus::spi::ExclusiveDevice<
+            embassy_stm32::spi::Spi<'static, embassy_stm32::mode::Async>,
+            embassy_stm32::gpio::Output<'static>,
+            embassy_time::Delay,
+        >,
+        embassy_stm32::exti::ExtiInput<'static>,
+        embassy_stm32::gpio::Output<'static>,
+    >,
+) -> ! {
+    runner.run().await
+}
+
+/// Drive the Microchip ENC28J60's SPI traffic.
+#[cfg(feature = "eth-enc28j60")]
+#[embassy_executor::task]
+pub async fn enc_runner(
+    runner: embassy_net_enc28j60::Runner<
+        'static,
+        embassy_stm32::spi::Spi<'static, embassy_stm32::mode::Async>,
+        embassy_stm32::gpio::Output<'static>,
+    >,
+) -> ! {
+    runner.run().await
+}
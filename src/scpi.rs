@@ -0,0 +1,223 @@
+use crate::net_util::generate_mac_address;
+use crate::time::clock_gettime;
+use core::cell::RefCell;
+use core::fmt::Write as _;
+use defmt::{info, warn};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::Stack;
+use embassy_stm32::uid::uid;
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+use embedded_io_async::{Read, Write};
+use heapless::String;
+
+/// The TCP port the SCPI interface is served on (the IANA "raw SCPI" port).
+const SCPI_PORT: u16 = 5025;
+
+/// The most recent filtered nasal-pressure reading, in pascals. Updated by the
+/// sampling task and queried by `MEASure:PRESsure?`.
+static LATEST_PRESSURE: Mutex<ThreadModeRawMutex, RefCell<f32>> = Mutex::new(RefCell::new(0.0));
+
+/// Record the latest filtered pressure reading for `MEASure:PRESsure?`.
+pub fn set_latest_pressure(pressure_pa: f32) {
+    LATEST_PRESSURE.lock(|p| *p.borrow_mut() = pressure_pa);
+}
+
+/// Serve the line-based SCPI interface, one connection at a time, so lab and
+/// instrument tooling that speaks SCPI can drive the device alongside the
+/// JSON-RPC server.
+#[embassy_executor::task]
+pub async fn serve(stack: Stack<'static>) -> ! {
+    let mut rx_buffer = [0u8; 256];
+    let mut tx_buffer = [0u8; 256];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        socket.set_keep_alive(Some(Duration::from_secs(5)));
+
+        if let Err(e) = socket.accept(SCPI_PORT).await {
+            warn!("SCPI accept error: {:?}", e);
+            continue;
+        }
+
+        info!("SCPI connection from {:?}", socket.remote_endpoint().unwrap());
+
+        let mut line: String<128> = String::new();
+        let mut buf = [0u8; 64];
+        'conn: loop {
+            let n = match socket.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+
+            for &byte in &buf[..n] {
+                match byte {
+                    b'\n' | b'\r' => {
+                        if !line.is_empty() {
+                            let mut response: String<128> = String::new();
+                            if let Some(reply) = dispatch(line.trim()) {
+                                response.push_str(&reply).ok();
+                                response.push_str("\r\n").ok();
+                                if socket.write(response.as_bytes()).await.is_err() {
+                                    break 'conn;
+                                }
+                            }
+                            line.clear();
+                        }
+                    }
+                    _ => {
+                        // Silently truncate pathologically long lines.
+                        line.push(byte as char).ok();
+                    }
+                }
+            }
+        }
+
+        info!("SCPI connection closed");
+    }
+}
+
+/// Dispatch a single SCPI command line, returning the response for a query or
+/// `None` for a command that produces no output.
+fn dispatch(line: &str) -> Option<String<128>> {
+    let query = line.ends_with('?');
+    // Only the keyword part is split into nodes; the parameter after the first
+    // whitespace is read separately by `value_of`.
+    let command = line
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .trim_end_matches('?');
+    let mut nodes = command.split(':');
+
+    let root = nodes.next().unwrap_or("");
+
+    if matches_exact(root, "*IDN") && query {
+        return Some(identify());
+    }
+
+    if matches(root, "SYST", "SYSTEM") {
+        if matches_exact(nodes.next().unwrap_or(""), "TIME") && query {
+            return Some(system_time());
+        }
+    } else if matches(root, "MEAS", "MEASURE") {
+        if matches(nodes.next().unwrap_or(""), "PRES", "PRESSURE") && query {
+            return Some(measure_pressure());
+        }
+    } else if matches(root, "CONF", "CONFIGURE") && !query {
+        return configure(&mut nodes, value_of(line));
+    }
+
+    warn!("Unrecognized SCPI command");
+    Some(error_reply())
+}
+
+/// Match a SCPI keyword token against its short and long forms, case-insensitively.
+fn matches(token: &str, short: &str, long: &str) -> bool {
+    eq_ignore_ascii_case(token, short) || eq_ignore_ascii_case(token, long)
+}
+
+/// Convenience for the common case where the short and long forms coincide.
+fn matches_exact(token: &str, keyword: &str) -> bool {
+    eq_ignore_ascii_case(token, keyword)
+}
+
+fn eq_ignore_ascii_case(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .all(|(x, y)| x.eq_ignore_ascii_case(&y))
+}
+
+/// The whitespace-separated parameter of a command, if any.
+fn value_of(line: &str) -> Option<&str> {
+    line.trim_end_matches('?').split_whitespace().nth(1)
+}
+
+fn identify() -> String<128> {
+    let mac = generate_mac_address();
+    let mut s: String<128> = String::new();
+    write!(
+        s,
+        "OpenPSG,{},{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x},",
+        env!("CARGO_BIN_NAME"),
+        mac[0],
+        mac[1],
+        mac[2],
+        mac[3],
+        mac[4],
+        mac[5],
+    )
+    .ok();
+    for byte in uid() {
+        write!(s, "{:02x}", byte).ok();
+    }
+    s
+}
+
+fn system_time() -> String<128> {
+    let mut s: String<128> = String::new();
+    match clock_gettime() {
+        Ok(tp) => {
+            let ts = rfc3339::format_unix(tp.seconds, tp.micros);
+            write!(s, "{}", ts).ok();
+        }
+        Err(_) => {
+            s.push_str("0").ok();
+        }
+    }
+    s
+}
+
+fn measure_pressure() -> String<128> {
+    let pressure = LATEST_PRESSURE.lock(|p| *p.borrow());
+    let mut s: String<128> = String::new();
+    write!(s, "{}", pressure).ok();
+    s
+}
+
+/// Validate a `CONFigure:ADC:{RATE,GAIN,CHANnel}` command and acknowledge it.
+///
+/// This is validate-only: the `Cs1237` exposes no runtime setter (it is
+/// configured once from a `Config` in `try_new`, which consumes its clock and
+/// data lines into the SPI peripheral), so the argument is range-checked to
+/// return an ACK/NAK but is not applied. The ADC configuration is fixed at the
+/// value `main` passes at construction.
+fn configure<'a>(
+    nodes: &mut impl Iterator<Item = &'a str>,
+    value: Option<&str>,
+) -> Option<String<128>> {
+    if !matches_exact(nodes.next().unwrap_or(""), "ADC") {
+        return Some(error_reply());
+    }
+
+    let value = match value {
+        Some(value) => value,
+        None => return Some(error_reply()),
+    };
+
+    let param = nodes.next().unwrap_or("");
+    let ok = if matches_exact(param, "RATE") {
+        matches!(value, "10" | "40" | "640" | "1280")
+    } else if matches_exact(param, "GAIN") {
+        matches!(value, "1" | "2" | "64" | "128")
+    } else if matches(param, "CHAN", "CHANNEL") {
+        matches!(value, "0" | "2" | "3")
+    } else {
+        false
+    };
+
+    if ok {
+        None
+    } else {
+        Some(error_reply())
+    }
+}
+
+fn error_reply() -> String<128> {
+    let mut s: String<128> = String::new();
+    s.push_str("ERROR").ok();
+    s
+}
@@ -0,0 +1,143 @@
+use crate::time::{clock_gettime, is_clock_synced};
+use crate::api::MAX_SIGNALS;
+use core::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+use embassy_net::tcp::Error as TcpReadError;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_jsonrpc::{RpcRequest, RpcServer, JSONRPC_VERSION};
+use heapless::Vec;
+use rfc3339::Timestamp;
+use serde::Serialize;
+
+/// Running count of ADC read errors observed by the sampling tasks.
+static ADC_ERRORS: AtomicU32 = AtomicU32::new(0);
+/// Running count of sample frames dropped because no consumer could take them.
+static DROPPED_FRAMES: AtomicU32 = AtomicU32::new(0);
+/// Bitmask of signal IDs currently sampling (bit `id`).
+static ACTIVE_SIGNALS: AtomicU32 = AtomicU32::new(0);
+/// Latest analog-supply (VDDA) reading in millivolts, `0` until first sampled.
+static SUPPLY_MILLIVOLTS: AtomicU32 = AtomicU32::new(0);
+/// Latest backup-battery (VBAT) reading in millivolts, `0` until first sampled.
+static VBAT_MILLIVOLTS: AtomicU32 = AtomicU32::new(0);
+/// Latest core temperature in milli-degrees Celsius.
+static CORE_TEMPERATURE_MILLIC: AtomicI32 = AtomicI32::new(0);
+
+/// Record an ADC read error.
+pub fn record_adc_error() {
+    ADC_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Record a dropped/overflowed sample frame.
+pub fn record_dropped_frame() {
+    DROPPED_FRAMES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Mark a signal as actively sampling or stopped. IDs of 32 or greater are not
+/// represented in the heartbeat's active-signal set.
+pub fn set_signal_active(id: u32, active: bool) {
+    if id >= 32 {
+        return;
+    }
+    let bit = 1 << id;
+    if active {
+        ACTIVE_SIGNALS.fetch_or(bit, Ordering::Relaxed);
+    } else {
+        ACTIVE_SIGNALS.fetch_and(!bit, Ordering::Relaxed);
+    }
+}
+
+/// Publish the latest internal-rail readings sampled by [`crate::monitor`]:
+/// analog supply (VDDA) and backup battery in millivolts, core temperature in
+/// milli-degrees Celsius.
+pub fn set_rails(supply_mv: u32, vbat_mv: u32, core_temp_millic: i32) {
+    SUPPLY_MILLIVOLTS.store(supply_mv, Ordering::Relaxed);
+    VBAT_MILLIVOLTS.store(vbat_mv, Ordering::Relaxed);
+    CORE_TEMPERATURE_MILLIC.store(core_temp_millic, Ordering::Relaxed);
+}
+
+/// A periodic device health report.
+#[derive(Serialize)]
+struct Heartbeat {
+    /// Monotonically increasing sequence number, so clients can detect a
+    /// missed heartbeat.
+    seq: u32,
+    /// Seconds since the device booted.
+    uptime: u64,
+    /// The current RTC timestamp.
+    timestamp: Timestamp,
+    /// Whether the RTC has been set since boot (vs. free-running from zero).
+    #[serde(rename = "clockSynced")]
+    clock_synced: bool,
+    /// Running count of ADC read errors.
+    #[serde(rename = "adcErrors")]
+    adc_errors: u32,
+    /// Running count of dropped/overflowed sample frames.
+    #[serde(rename = "droppedFrames")]
+    dropped_frames: u32,
+    /// The signal IDs currently sampling.
+    #[serde(rename = "activeSignals")]
+    active_signals: Vec<u32, MAX_SIGNALS>,
+    /// Analog supply voltage (VDDA) in millivolts; a sag here biases the
+    /// CS1237 readings. `0` until the monitor task has sampled once.
+    #[serde(rename = "supplyMillivolts")]
+    supply_millivolts: u32,
+    /// Backup-battery voltage (VBAT) in millivolts.
+    #[serde(rename = "vbatMillivolts")]
+    vbat_millivolts: u32,
+    /// Core temperature in milli-degrees Celsius, for spotting thermal drift.
+    #[serde(rename = "coreTemperatureMillic")]
+    core_temperature_millic: i32,
+}
+
+/// Emit an `openpsg.heartbeat` notification once per second so clients can
+/// detect a wedged ADC, an unsynced clock, or a silent device without waiting
+/// on `openpsg.values` traffic.
+#[embassy_executor::task]
+pub async fn heartbeat(rpc_server: &'static RpcServer<'static, TcpReadError>) -> ! {
+    let boot = Instant::now();
+    let mut seq: u32 = 0;
+
+    loop {
+        Timer::after(Duration::from_secs(1)).await;
+
+        let mask = ACTIVE_SIGNALS.load(Ordering::Relaxed);
+        let mut active_signals: Vec<u32, MAX_SIGNALS> = Vec::new();
+        for id in 0..32 {
+            if mask & (1 << id) != 0 {
+                active_signals.push(id).ok();
+            }
+        }
+
+        let now = clock_gettime().unwrap_or(crate::time::Timespec {
+            seconds: 0,
+            micros: 0,
+        });
+
+        let payload = Heartbeat {
+            seq,
+            uptime: (Instant::now() - boot).as_secs(),
+            timestamp: rfc3339::format_unix(now.seconds, now.micros),
+            clock_synced: is_clock_synced(),
+            adc_errors: ADC_ERRORS.load(Ordering::Relaxed),
+            dropped_frames: DROPPED_FRAMES.load(Ordering::Relaxed),
+            active_signals,
+            supply_millivolts: SUPPLY_MILLIVOLTS.load(Ordering::Relaxed),
+            vbat_millivolts: VBAT_MILLIVOLTS.load(Ordering::Relaxed),
+            core_temperature_millic: CORE_TEMPERATURE_MILLIC.load(Ordering::Relaxed),
+        };
+
+        let notification: RpcRequest<&Heartbeat> = RpcRequest {
+            jsonrpc: JSONRPC_VERSION,
+            id: None,
+            method: "openpsg.heartbeat",
+            params: Some(&payload),
+        };
+
+        let mut notification_json = [0u8; 384];
+        if let Ok(len) = serde_json_core::to_slice(&notification, &mut notification_json) {
+            // Best-effort: a disconnected client simply misses heartbeats.
+            let _ = rpc_server.notify(&notification_json[..len]).await;
+        }
+
+        seq = seq.wrapping_add(1);
+    }
+}
@@ -19,6 +19,7 @@
 use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Weekday};
 use core::cell::RefCell;
 use core::result::Result;
+use core::sync::atomic::{AtomicBool, Ordering};
 use embassy_stm32::pac;
 use embassy_stm32::rtc::{DateTime, DayOfWeek, Rtc, RtcConfig, RtcError};
 use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
@@ -26,6 +27,15 @@ use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
 static RTC: Mutex<ThreadModeRawMutex, RefCell<Option<Rtc>>> = Mutex::new(RefCell::new(None));
 static MICROS_OFFSET: Mutex<ThreadModeRawMutex, RefCell<u32>> = Mutex::new(RefCell::new(0));
 
+/// Whether the RTC has been set since boot, distinguishing a disciplined clock
+/// from one free-running from zero.
+static CLOCK_SYNCED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the RTC has been set since boot via [`clock_settime`].
+pub fn is_clock_synced() -> bool {
+    CLOCK_SYNCED.load(Ordering::Relaxed)
+}
+
 /// A timespec structure representing a time in seconds and microseconds.
 pub struct Timespec {
     pub seconds: u64,
@@ -77,6 +87,8 @@ pub fn clock_settime(tp: &Timespec) -> Result<(), RtcError> {
             MICROS_OFFSET.lock(|micros_offset| {
                 *micros_offset.borrow_mut() = tp.micros;
             });
+
+            CLOCK_SYNCED.store(true, Ordering::Relaxed);
         } else {
             return Err(RtcError::NotRunning);
         }